@@ -0,0 +1,120 @@
+//! Configuration types deserialized from the proxy's YAML config file.
+
+use std::collections::HashMap;
+use std::net;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AppConfig {
+    pub admin: Option<AdminConfig>,
+    pub buffer_size: Option<usize>,
+    pub proxies: Vec<ProxyConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AdminConfig {
+    pub addr: Option<net::SocketAddr>,
+    pub metrics_interval_secs: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProxyConfig {
+    pub label: String,
+    pub discovery: DiscoveryConfig,
+    pub servers: Vec<ServerConfig>,
+    pub client: Option<ClientConfig>,
+    pub max_waiters: Option<usize>,
+}
+
+/// Where a proxy discovers the backends it balances across.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind")]
+pub enum DiscoveryConfig {
+    Namerd(NamerdConfig),
+    DnsSrv(DnsConfig),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct NamerdConfig {
+    pub addr: net::SocketAddr,
+    pub path: String,
+    pub namespace: Option<String>,
+    pub interval_secs: Option<u64>,
+}
+
+/// Discovers backends by periodically querying a DNS SRV record.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DnsConfig {
+    pub name: String,
+    pub interval_secs: Option<u64>,
+}
+
+/// How a proxy listens for inbound connections.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind")]
+pub enum ServerConfig {
+    Tcp {
+        addr: net::SocketAddr,
+
+        /// When set, an inbound PROXY protocol header is parsed off the front of each
+        /// connection and the client address it carries replaces the connection's peer
+        /// address; no metric is labeled with that address.
+        #[serde(default)]
+        proxy_protocol: bool,
+
+        /// How long a connection may sit without a successful read or write before it
+        /// is torn down. Off by default.
+        idle_timeout_secs: Option<u64>,
+    },
+    Tls {
+        addr: net::SocketAddr,
+        alpn_protocols: Option<Vec<String>>,
+        default_identity: Option<TlsServerIdentity>,
+        identities: Option<HashMap<String, TlsServerIdentity>>,
+
+        #[serde(default)]
+        proxy_protocol: bool,
+
+        /// How long a connection may sit without a successful read or write before it
+        /// is torn down. Off by default.
+        idle_timeout_secs: Option<u64>,
+    },
+    Unix {
+        path: String,
+
+        /// Whether linkerd owns the socket file: if set, a stale file at `path` is
+        /// removed before binding and the path is unlinked again on shutdown.
+        #[serde(default = "default_true")]
+        owned: bool,
+
+        /// How long a connection may sit without a successful read or write before it
+        /// is torn down. Off by default.
+        idle_timeout_secs: Option<u64>,
+    },
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// How a proxy dials backend connections.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ClientConfig {
+    pub tls: Option<TlsClientConfig>,
+
+    /// When set, a PROXY protocol v2 header carrying the accepted peer's address is
+    /// written to each backend connection before any application bytes.
+    #[serde(default)]
+    pub proxy_protocol: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TlsClientConfig {
+    pub dns_name: String,
+    pub trust_certs: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TlsServerIdentity {
+    pub private_key: String,
+    pub cert_chain: String,
+}
@@ -10,6 +10,7 @@ use std::collections::{VecDeque, HashMap};
 use std::fs::File;
 use std::io::{self, BufReader};
 use std::net::{self, SocketAddr};
+use std::path::Path;
 use std::rc::Rc;
 use std::time::Duration;
 use tacho::{self, Tacho};
@@ -21,16 +22,19 @@ mod admin_http;
 mod sni;
 pub mod config;
 
-use WeightedAddr;
+use {Update, WeightedAddr};
+use discovery::Resolver;
+use dns::DnsResolver;
 use lb::{Balancer, Acceptor, Connector, PlainAcceptor, PlainConnector, SecureAcceptor,
-         SecureConnector};
-use namerd;
+         SecureConnector, UnixAcceptor};
+use namerd::NamerdResolver;
 use self::config::*;
 use self::sni::Sni;
 
 const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
 const DEFAULT_MAX_WAITERS: usize = 8;
 const DEFAULT_NAMERD_SECONDS: u64 = 60;
+const DEFAULT_DNS_SECONDS: u64 = 60;
 const DEFAULT_METRICS_SECONDS: u64 = 10;
 
 fn default_admin_addr() -> net::SocketAddr {
@@ -49,15 +53,15 @@ pub fn configure(app: AppConfig) -> (Admin, Proxies) {
 
     let Tacho { metrics, aggregator, report } = Tacho::default();
 
-    let mut namerds = VecDeque::new();
+    let mut discoveries = VecDeque::new();
     let mut proxies = VecDeque::new();
     let mut proxy_configs = app.proxies;
     for _ in 0..proxy_configs.len() {
-        let ProxyConfig { label, namerd, servers, client, max_waiters, .. } = proxy_configs.pop()
+        let ProxyConfig { label, discovery, servers, client, max_waiters, .. } = proxy_configs.pop()
             .unwrap();
         let (addrs_tx, addrs_rx) = mpsc::channel(1);
-        namerds.push_back(Namerd {
-            config: namerd,
+        discoveries.push_back(Discovery {
+            config: discovery,
             sender: addrs_tx,
             metrics: metrics.clone(),
         });
@@ -65,7 +69,7 @@ pub fn configure(app: AppConfig) -> (Admin, Proxies) {
             client: client,
             server: ProxyServer {
                 label: label,
-                addrs: Box::new(addrs_rx.fuse()),
+                updates: Box::new(addrs_rx.fuse()),
                 servers: servers,
                 buf: transfer_buf.clone(),
                 max_waiters: max_waiters.unwrap_or(DEFAULT_MAX_WAITERS),
@@ -85,7 +89,7 @@ pub fn configure(app: AppConfig) -> (Admin, Proxies) {
     let admin = Admin {
         addr: addr,
         metrics_interval: Duration::from_secs(interval_s),
-        namerds: namerds,
+        discoveries: discoveries,
         aggregator: aggregator,
         report: report,
     };
@@ -112,7 +116,7 @@ impl<L: Loader> Runner for L {
 pub struct Admin {
     addr: net::SocketAddr,
     metrics_interval: Duration,
-    namerds: VecDeque<Namerd>,
+    discoveries: VecDeque<Discovery>,
     aggregator: tacho::Aggregator,
     report: BiLock<tacho::Report>,
 }
@@ -121,9 +125,9 @@ impl Loader for Admin {
     fn load(self, handle: Handle) -> io::Result<(SocketAddr, Running)> {
         let mut running = Running::new();
         {
-            let mut namerds = self.namerds;
-            for _ in 0..namerds.len() {
-                let (_, f) = namerds.pop_front().unwrap().load(handle.clone())?;
+            let mut discoveries = self.discoveries;
+            for _ in 0..discoveries.len() {
+                let (_, f) = discoveries.pop_front().unwrap().load(handle.clone())?;
                 running.register(f.map_err(|_| io::ErrorKind::Other.into()));
             }
         }
@@ -168,33 +172,143 @@ impl Loader for Admin {
 }
 
 
-pub struct Namerd {
-    pub config: NamerdConfig,
-    pub sender: mpsc::Sender<Vec<WeightedAddr>>,
+/// Drives a single proxy's service discovery, diffing each resolved address-set
+/// snapshot against the last one and forwarding the resulting `Update`s to its
+/// `ProxyServer` over a channel.
+pub struct Discovery {
+    pub config: DiscoveryConfig,
+    pub sender: mpsc::Sender<Vec<Update>>,
     pub metrics: tacho::Metrics,
 }
-impl Loader for Namerd {
+impl Loader for Discovery {
     type Run = Box<Future<Item = (), Error = io::Error>>;
     fn load(self, handle: Handle) -> io::Result<(SocketAddr, Self::Run)> {
-        let path = self.config.path;
-        let addr = self.config.addr;
-        let interval_secs = self.config.interval_secs.unwrap_or(DEFAULT_NAMERD_SECONDS);
-        let interval = Duration::from_secs(interval_secs);
-        let ns = self.config.namespace.clone().unwrap_or_else(|| "default".into());
-        info!("Updating {} in {} from {} every {}s",
-              path,
-              ns,
-              addr,
-              interval_secs);
-        let addrs = {
-            let client = Client::new(&handle);
-            namerd::resolve(self.config.addr, client, interval, &ns, &path, self.metrics)
+        let (local_addr, addrs) = match self.config {
+            DiscoveryConfig::Namerd(c) => {
+                let interval_secs = c.interval_secs.unwrap_or(DEFAULT_NAMERD_SECONDS);
+                let ns = c.namespace.clone().unwrap_or_else(|| "default".into());
+                info!("Updating {} in {} from {} every {}s", c.path, ns, c.addr, interval_secs);
+                let client = Client::new(&handle);
+                let resolver = NamerdResolver::new(c.addr,
+                                                    client,
+                                                    Duration::from_secs(interval_secs),
+                                                    &ns,
+                                                    &c.path,
+                                                    self.metrics);
+                (c.addr, resolver.resolve())
+            }
+            DiscoveryConfig::DnsSrv(c) => {
+                let interval_secs = c.interval_secs.unwrap_or(DEFAULT_DNS_SECONDS);
+                info!("Updating {} from DNS SRV every {}s", c.name, interval_secs);
+                let resolver = DnsResolver::new(c.name,
+                                                 Duration::from_secs(interval_secs),
+                                                 handle.clone(),
+                                                 self.metrics);
+                let addr: SocketAddr = "0.0.0.0:0".parse().unwrap();
+                (addr, resolver.resolve())
+            }
         };
+        let mut known: Vec<WeightedAddr> = Vec::new();
+        let updates = addrs.map(move |next| {
+                let updates = diff_updates(&known, &next);
+                known = next;
+                updates
+            })
+            .filter(|updates: &Vec<Update>| !updates.is_empty());
         let driver = {
             let sink = self.sender.sink_map_err(|_| error!("sink error"));
-            addrs.forward(sink).map_err(|_| io::ErrorKind::Other.into()).map(|_| {})
+            updates.forward(sink).map_err(|_| io::ErrorKind::Other.into()).map(|_| {})
         };
-        Ok((addr, Box::new(driver)))
+        Ok((local_addr, Box::new(driver)))
+    }
+}
+
+/// Diffs a newly resolved address set against the last known one.
+///
+/// A resolver error or a namerd "neg" response carries no addresses at all, which the
+/// resolver itself already treats as "no change" and never surfaces as a snapshot here
+/// (see `namerd::parse_chunks`); every snapshot `diff_updates` sees is an explicit one,
+/// so an empty `next` legitimately means every known endpoint was just removed.
+fn diff_updates(known: &[WeightedAddr], next: &[WeightedAddr]) -> Vec<Update> {
+    let mut updates = Vec::new();
+
+    let removed: Vec<SocketAddr> = known.iter()
+        .filter(|k| !next.iter().any(|n| n.0 == k.0))
+        .map(|k| k.0)
+        .collect();
+    if !removed.is_empty() {
+        updates.push(Update::Remove(removed));
+    }
+
+    let mut added = Vec::new();
+    for n in next {
+        match known.iter().find(|k| k.0 == n.0) {
+            None => added.push(n.clone()),
+            Some(k) if k.1 != n.1 => updates.push(Update::Reweight(n.0, n.1)),
+            Some(_) => {}
+        }
+    }
+    if !added.is_empty() {
+        updates.push(Update::Add(added));
+    }
+
+    updates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn adds_new_endpoints() {
+        let known = vec![];
+        let next = vec![WeightedAddr(addr("10.0.0.1:80"), 1.0)];
+        assert_eq!(diff_updates(&known, &next), vec![Update::Add(next.clone())]);
+    }
+
+    #[test]
+    fn removes_dropped_endpoints() {
+        let known = vec![WeightedAddr(addr("10.0.0.1:80"), 1.0)];
+        let next = vec![];
+        assert_eq!(diff_updates(&known, &next), vec![Update::Remove(vec![addr("10.0.0.1:80")])]);
+    }
+
+    #[test]
+    fn reweights_changed_endpoints() {
+        let known = vec![WeightedAddr(addr("10.0.0.1:80"), 1.0)];
+        let next = vec![WeightedAddr(addr("10.0.0.1:80"), 2.0)];
+        assert_eq!(diff_updates(&known, &next), vec![Update::Reweight(addr("10.0.0.1:80"), 2.0)]);
+    }
+
+    #[test]
+    fn unchanged_endpoints_produce_no_updates() {
+        let known = vec![WeightedAddr(addr("10.0.0.1:80"), 1.0)];
+        let next = known.clone();
+        assert_eq!(diff_updates(&known, &next), vec![]);
+    }
+
+    #[test]
+    fn shrinking_to_empty_removes_everything() {
+        // An empty `next` here is an explicit signal (a namerd "bound" response with no
+        // addrs, or DNS resolving zero SRV targets) -- the resolver layer already turns
+        // "no signal" (errors, "neg" responses) into a skipped poll rather than an empty
+        // snapshot, so `diff_updates` never needs to special-case an empty `next`.
+        let known = vec![WeightedAddr(addr("10.0.0.1:80"), 1.0), WeightedAddr(addr("10.0.0.2:80"), 1.0)];
+        let next = vec![];
+        let updates = diff_updates(&known, &next);
+        assert_eq!(updates.len(), 1);
+        match updates[0] {
+            Update::Remove(ref removed) => {
+                assert_eq!(removed.len(), 2);
+                assert!(removed.contains(&addr("10.0.0.1:80")));
+                assert!(removed.contains(&addr("10.0.0.2:80")));
+            }
+            ref other => panic!("expected Remove, got {:?}", other),
+        }
     }
 }
 
@@ -224,9 +338,10 @@ pub struct Proxy {
 impl Loader for Proxy {
     type Run = Running;
     fn load(self, handle: Handle) -> io::Result<(SocketAddr, Running)> {
+        let proxy_protocol = self.client.as_ref().map(|c| c.proxy_protocol).unwrap_or(false);
         match self.client.and_then(|c| c.tls) {
             None => {
-                let conn = PlainConnector::new(handle.clone());
+                let conn = PlainConnector::new(handle.clone()).with_proxy_protocol(proxy_protocol);
                 let f = self.server.load(&handle, conn).expect("b");
                 Ok(f)
             }
@@ -240,7 +355,8 @@ impl Loader for Proxy {
                             .expect("certificate error");
                     }
                 };
-                let conn = SecureConnector::new(c.dns_name.clone(), tls, handle.clone());
+                let conn = SecureConnector::new(c.dns_name.clone(), tls, handle.clone())
+                    .with_proxy_protocol(proxy_protocol);
                 let f = self.server.load(&handle, conn).expect("a");
                 Ok(f)
             }
@@ -251,18 +367,18 @@ impl Loader for Proxy {
 pub struct ProxyServer {
     pub label: String,
     pub servers: Vec<ServerConfig>,
-    pub addrs: Box<Stream<Item = Vec<WeightedAddr>, Error = ()>>,
+    pub updates: Box<Stream<Item = Vec<Update>, Error = ()>>,
     pub buf: Rc<RefCell<Vec<u8>>>,
     pub max_waiters: usize,
     pub metrics: tacho::Metrics,
 }
 impl ProxyServer {
     fn load<C>(self, handle: &Handle, conn: C) -> io::Result<(SocketAddr, Running)>
-        where C: Connector + 'static
+        where C: Connector + Clone + 'static
     {
-        let addrs = self.addrs.map_err(|_| io::ErrorKind::Other.into());
+        let updates = self.updates.map_err(|_| io::ErrorKind::Other.into());
         let metrics = self.metrics.clone().labeled("proxy".into(), self.label.into());
-        let bal = Balancer::new(addrs, conn, self.buf.clone(), metrics.clone())
+        let bal = Balancer::new(updates, conn, self.buf.clone(), metrics.clone())
             .into_shared(self.max_waiters, handle.clone());
 
         // Placeholder for our local listening SocketAddr.
@@ -275,10 +391,13 @@ impl ProxyServer {
             let handle = handle.clone();
             let bal = bal.clone();
             match *s {
-                ServerConfig::Tcp { ref addr } => {
+                ServerConfig::Tcp { ref addr, proxy_protocol, idle_timeout_secs } => {
+                    let idle_timeout = idle_timeout_secs.map(Duration::from_secs);
                     let metrics = metrics.clone().labeled("srv".into(), format!("{}", addr));
-                    let acceptor = PlainAcceptor::new(handle, metrics);
-                    let (bound_addr, forwarder) = acceptor.accept(addr);
+                    let acceptor = PlainAcceptor::new(handle, metrics)
+                        .with_proxy_protocol(proxy_protocol)
+                        .with_idle_timeout(idle_timeout);
+                    let (bound_addr, forwarder) = acceptor.accept(addr).expect("unable to bind");
                     local_addr = bound_addr;
                     let f = forwarder.forward(bal).map(|_| {});
                     running.register(f);
@@ -287,6 +406,8 @@ impl ProxyServer {
                                     ref alpn_protocols,
                                     ref default_identity,
                                     ref identities,
+                                    proxy_protocol,
+                                    idle_timeout_secs,
                                     .. } => {
                     let mut tls = rustls::ServerConfig::new();
                     tls.cert_resolver = load_cert_resolver(identities, default_identity);
@@ -294,13 +415,24 @@ impl ProxyServer {
                         tls.set_protocols(protos);
                     }
 
+                    let idle_timeout = idle_timeout_secs.map(Duration::from_secs);
                     let metrics = metrics.clone().labeled("srv".into(), format!("{}", addr));
-                    let acceptor = SecureAcceptor::new(handle, tls, metrics);
-                    let (bound_addr, forwarder) = acceptor.accept(addr);
+                    let acceptor = SecureAcceptor::new(handle, tls, metrics)
+                        .with_proxy_protocol(proxy_protocol)
+                        .with_idle_timeout(idle_timeout);
+                    let (bound_addr, forwarder) = acceptor.accept(addr).expect("unable to bind");
                     local_addr = bound_addr;
                     let f = forwarder.forward(bal).map(|_| {});
                     running.register(f);
                 }
+                ServerConfig::Unix { ref path, owned, idle_timeout_secs } => {
+                    let idle_timeout = idle_timeout_secs.map(Duration::from_secs);
+                    let metrics = metrics.clone().labeled("srv".into(), path.clone());
+                    let acceptor = UnixAcceptor::new(handle, metrics).with_idle_timeout(idle_timeout);
+                    let forwarder = acceptor.accept(Path::new(path), owned).expect("unable to bind");
+                    let f = forwarder.forward(bal).map(|_| {});
+                    running.register(f);
+                }
             }
         }
         Ok((local_addr, running))
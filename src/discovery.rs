@@ -0,0 +1,20 @@
+//! Service discovery: resolving a configured name to a weighted set of backend
+//! addresses.
+
+use futures::Stream;
+
+use WeightedAddr;
+
+/// A stream of complete address-set snapshots for a discovered name.
+///
+/// Each item replaces the previously known set of endpoints; the stream never
+/// completes.
+pub type AddrsStream = Box<Stream<Item = Vec<WeightedAddr>, Error = ()>>;
+
+/// A source of backend endpoints for a named service.
+///
+/// Implementations poll on their own schedule (namerd over HTTP, DNS SRV lookups,
+/// etc.) and are selected per-proxy from config.
+pub trait Resolver {
+    fn resolve(self) -> AddrsStream;
+}
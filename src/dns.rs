@@ -0,0 +1,250 @@
+//! DNS SRV-based service discovery, for deployments (Consul, Kubernetes headless
+//! services, etc.) that publish endpoints as SRV records instead of running namerd.
+
+use futures::{Future, Stream, future};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time;
+use tacho::{self, Timing};
+use tokio_core::reactor::Handle;
+use tokio_timer::Timer;
+use trust_dns_resolver::ResolverFuture;
+use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+
+use discovery::{AddrsStream, Resolver};
+
+type AddrsFuture = Box<Future<Item = Option<Vec<::WeightedAddr>>, Error = ()>>;
+
+#[derive(Clone)]
+struct Stats {
+    metrics: tacho::Metrics,
+    lookup_latency_ms: tacho::StatKey,
+    success_count: tacho::CounterKey,
+    failure_count: tacho::CounterKey,
+}
+impl Stats {
+    fn new(metrics: tacho::Metrics) -> Stats {
+        let metrics = metrics.labeled("service".into(), "dns".into());
+        Stats {
+            lookup_latency_ms: metrics.scope().timing_ms("dns_lookup_latency_ms".into()),
+            success_count: metrics.scope().counter("dns_success_count".into()),
+            failure_count: metrics.scope().counter("dns_failure_count".into()),
+            metrics: metrics,
+        }
+    }
+}
+
+/// Resolves a name to a set of addresses by periodically issuing a DNS SRV query and
+/// resolving each target's A/AAAA records.
+pub struct DnsResolver {
+    name: String,
+    period: time::Duration,
+    handle: Handle,
+    metrics: tacho::Metrics,
+}
+
+impl DnsResolver {
+    pub fn new(name: String, period: time::Duration, handle: Handle, metrics: tacho::Metrics) -> DnsResolver {
+        DnsResolver {
+            name: name,
+            period: period,
+            handle: handle,
+            metrics: metrics,
+        }
+    }
+}
+
+impl Resolver for DnsResolver {
+    fn resolve(self) -> AddrsStream {
+        let stats = Stats::new(self.metrics);
+        let (resolver, background) =
+            ResolverFuture::new(ResolverConfig::default(), ResolverOpts::default(), &self.handle);
+        self.handle.spawn(background);
+
+        let name = self.name;
+        let init = lookup(resolver.clone(), name.clone(), stats.clone());
+        let updates = Timer::default()
+            .interval(self.period)
+            .then(move |_| lookup(resolver.clone(), name.clone(), stats.clone()));
+        Box::new(init.into_stream().chain(updates).filter_map(|opt| opt))
+    }
+}
+
+fn lookup(resolver: ResolverFuture, name: String, stats: Stats) -> AddrsFuture {
+    debug!("Looking up SRV records for {}", name);
+    let rsp = future::lazy(|| Ok(tacho::Timing::start())).and_then(move |start_t| {
+        resolver.lookup_srv(&name)
+            .then(|rsp| match rsp {
+                Ok(srv) => {
+                    let records = srv.iter()
+                        .map(|r| {
+                            SrvRecord {
+                                priority: r.priority(),
+                                weight: r.weight(),
+                                target: r.target().to_utf8(),
+                                port: r.port(),
+                            }
+                        })
+                        .collect();
+                    resolve_targets(resolver.clone(), records)
+                }
+                Err(e) => {
+                    info!("error: SRV lookup failed: {}", e);
+                    future::ok(None).boxed()
+                }
+            })
+            .then(move |rsp| {
+                let mut rec = stats.metrics.recorder();
+                rec.add(&stats.lookup_latency_ms, start_t.elapsed_ms());
+                if rsp.as_ref().ok().and_then(|r| r.as_ref()).is_some() {
+                    rec.incr(&stats.success_count, 1);
+                } else {
+                    rec.incr(&stats.failure_count, 1);
+                }
+                rsp
+            })
+    });
+    Box::new(rsp)
+}
+
+/// A single SRV record, with the fields we care about already owned so we can build
+/// the weighted address list without borrowing from the trust-dns response.
+#[derive(Clone)]
+struct SrvRecord {
+    priority: u16,
+    weight: u16,
+    target: String,
+    port: u16,
+}
+
+fn resolve_targets(resolver: ResolverFuture, records: Vec<SrvRecord>) -> AddrsFuture {
+    if records.is_empty() {
+        return Box::new(future::ok(Some(vec![])));
+    }
+    let lookups = records.iter()
+        .map(|r| {
+            let target = r.target.clone();
+            resolver.lookup_ip(&target)
+                .then(move |res| {
+                    let ips: Vec<IpAddr> = res.map(|r| r.iter().collect()).unwrap_or_else(|e| {
+                        info!("error: failed to resolve SRV target {}: {}", target, e);
+                        vec![]
+                    });
+                    Ok((target, ips))
+                })
+        })
+        .collect::<Vec<_>>();
+    let f = future::join_all(lookups).map(move |resolved: Vec<(String, Vec<IpAddr>)>| {
+        let targets: HashMap<String, Vec<IpAddr>> = resolved.into_iter().collect();
+        let addrs = weighted_addrs(&records, &targets);
+        if addrs.is_empty() {
+            // No target in the lowest-priority group resolved: treat the poll as a
+            // failed lookup ("no change") rather than as an explicit empty set.
+            info!("error: no SRV target resolved to an address");
+            None
+        } else {
+            Some(addrs)
+        }
+    });
+    Box::new(f)
+}
+
+/// Translates a set of SRV records (and their resolved addresses) into
+/// `WeightedAddr`s: only the lowest-priority records that actually resolved to at
+/// least one IP are used, and weight is distributed proportionally to each record's
+/// `weight` field, normalized over just those records so the results always sum to
+/// 1.0 (an all-zero-weight group is treated as uniform).
+fn weighted_addrs(records: &[SrvRecord], targets: &HashMap<String, Vec<IpAddr>>) -> Vec<::WeightedAddr> {
+    let min_priority = match records.iter().map(|r| r.priority).min() {
+        Some(p) => p,
+        None => return vec![],
+    };
+    let group: Vec<&SrvRecord> = records.iter()
+        .filter(|r| r.priority == min_priority && targets.get(&r.target).map_or(false, |ips| !ips.is_empty()))
+        .collect();
+    let total_weight: u32 = group.iter().map(|r| r.weight as u32).sum();
+
+    let mut addrs = Vec::new();
+    for r in &group {
+        let ips = match targets.get(&r.target) {
+            Some(ips) if !ips.is_empty() => ips,
+            _ => continue,
+        };
+        let record_weight = if total_weight == 0 {
+            1.0 / (group.len() as f32)
+        } else {
+            (r.weight as f32) / (total_weight as f32)
+        };
+        let per_addr_weight = record_weight / (ips.len() as f32);
+        for ip in ips {
+            addrs.push(::WeightedAddr(SocketAddr::new(*ip, r.port), per_addr_weight));
+        }
+    }
+    addrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(priority: u16, weight: u16, target: &str, port: u16) -> SrvRecord {
+        SrvRecord {
+            priority: priority,
+            weight: weight,
+            target: target.into(),
+            port: port,
+        }
+    }
+
+    fn targets(pairs: &[(&str, &str)]) -> HashMap<String, Vec<IpAddr>> {
+        pairs.iter().map(|&(name, ip)| (name.into(), vec![ip.parse().unwrap()])).collect()
+    }
+
+    #[test]
+    fn distributes_weight_proportionally() {
+        let records = vec![record(0, 1, "a", 80), record(0, 3, "b", 80)];
+        let targets = targets(&[("a", "10.0.0.1"), ("b", "10.0.0.2")]);
+        let addrs = weighted_addrs(&records, &targets);
+        assert_eq!(addrs.len(), 2);
+        let a = addrs.iter().find(|a| a.0.ip() == "10.0.0.1".parse::<IpAddr>().unwrap()).unwrap();
+        let b = addrs.iter().find(|a| a.0.ip() == "10.0.0.2".parse::<IpAddr>().unwrap()).unwrap();
+        assert_eq!(a.1, 0.25);
+        assert_eq!(b.1, 0.75);
+    }
+
+    #[test]
+    fn treats_all_zero_weight_group_as_uniform() {
+        let records = vec![record(0, 0, "a", 80), record(0, 0, "b", 80)];
+        let targets = targets(&[("a", "10.0.0.1"), ("b", "10.0.0.2")]);
+        let addrs = weighted_addrs(&records, &targets);
+        assert_eq!(addrs.len(), 2);
+        assert_eq!(addrs[0].1, 0.5);
+        assert_eq!(addrs[1].1, 0.5);
+    }
+
+    #[test]
+    fn prefers_lowest_priority_group() {
+        let records = vec![record(0, 1, "a", 80), record(1, 1, "b", 80)];
+        let targets = targets(&[("a", "10.0.0.1"), ("b", "10.0.0.2")]);
+        let addrs = weighted_addrs(&records, &targets);
+        assert_eq!(addrs.len(), 1);
+        assert_eq!(addrs[0].0.ip(), "10.0.0.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn skips_records_with_no_resolved_ips() {
+        let records = vec![record(0, 1, "a", 80), record(0, 1, "b", 80)];
+        let targets = targets(&[("a", "10.0.0.1")]);
+        let addrs = weighted_addrs(&records, &targets);
+        assert_eq!(addrs.len(), 1);
+        assert_eq!(addrs[0].0.ip(), "10.0.0.1".parse::<IpAddr>().unwrap());
+        // "b" never resolved, so its weight must not inflate the denominator: the
+        // surviving address should get all the weight, not half of it.
+        assert_eq!(addrs[0].1, 1.0);
+    }
+
+    #[test]
+    fn empty_records_yield_no_addresses() {
+        assert!(weighted_addrs(&[], &HashMap::new()).is_empty());
+    }
+}
@@ -0,0 +1,324 @@
+//! Listens for inbound connections and produces a stream of accepted `Socket`s.
+
+use futures::{Async, Future, Poll, Stream};
+use futures::future;
+use rustls;
+use std::fs;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+use tacho;
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::Handle;
+use tokio_timer::Timer;
+use tokio_uds::UnixListener;
+
+use super::proxy_protocol;
+use super::socket::Socket;
+
+/// The maximum number of bytes we'll buffer while looking for a PROXY protocol header
+/// before giving up on the connection. v1's 107-byte text line is the larger of the two
+/// formats; `proxy_protocol::decode` enforces v2's tighter 52-byte limit itself once it
+/// has read enough of the header to know which format it's looking at.
+const MAX_HEADER_LEN: usize = proxy_protocol::V1_MAX_LEN;
+
+pub type Forwarder = Box<Stream<Item = Socket, Error = io::Error>>;
+
+/// Binds a listening socket and produces a stream of accepted connections.
+pub trait Acceptor {
+    fn accept(&self, addr: &SocketAddr) -> io::Result<(SocketAddr, Forwarder)>;
+}
+
+pub struct PlainAcceptor {
+    handle: Handle,
+    metrics: tacho::Metrics,
+    proxy_protocol: bool,
+    idle_timeout: Option<Duration>,
+    idle_timer: Timer,
+}
+
+impl PlainAcceptor {
+    pub fn new(handle: Handle, metrics: tacho::Metrics) -> PlainAcceptor {
+        PlainAcceptor {
+            handle: handle,
+            metrics: metrics,
+            proxy_protocol: false,
+            idle_timeout: None,
+            idle_timer: Timer::default(),
+        }
+    }
+
+    /// Enables parsing of an inbound PROXY protocol header on every accepted connection.
+    pub fn with_proxy_protocol(mut self, enabled: bool) -> PlainAcceptor {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    /// Fails a connection that neither reads nor writes for `duration`.
+    pub fn with_idle_timeout(mut self, duration: Option<Duration>) -> PlainAcceptor {
+        self.idle_timeout = duration;
+        self
+    }
+}
+
+impl Acceptor for PlainAcceptor {
+    fn accept(&self, addr: &SocketAddr) -> io::Result<(SocketAddr, Forwarder)> {
+        let listener = TcpListener::bind(addr, &self.handle)?;
+        let local_addr = listener.local_addr()?;
+        let proxy_protocol = self.proxy_protocol;
+        let idle_timeout = self.idle_timeout;
+        let idle_timer = self.idle_timer.clone();
+        let dropped = self.metrics.clone().scope().counter("connection_proxy_protocol_error_count".into());
+        let metrics = self.metrics.clone();
+        let idle_metrics = self.metrics.clone();
+        let incoming = listener.incoming()
+            .and_then(move |(io, _peer)| accept_plain(io, proxy_protocol))
+            .filter_map(move |res| match res {
+                Ok(sock) => Some(sock),
+                Err(e) => {
+                    info!("dropping connection with malformed PROXY protocol header: {}", e);
+                    metrics.clone().recorder().incr(&dropped, 1);
+                    None
+                }
+            })
+            .map(move |sock| match idle_timeout {
+                Some(d) => sock.with_idle_timeout(d, idle_timer.clone(), idle_metrics.clone()),
+                None => sock,
+            });
+        Ok((local_addr, Box::new(incoming)))
+    }
+}
+
+pub struct SecureAcceptor {
+    handle: Handle,
+    tls: Rc<rustls::ServerConfig>,
+    metrics: tacho::Metrics,
+    proxy_protocol: bool,
+    idle_timeout: Option<Duration>,
+    idle_timer: Timer,
+}
+
+impl SecureAcceptor {
+    pub fn new(handle: Handle, tls: rustls::ServerConfig, metrics: tacho::Metrics) -> SecureAcceptor {
+        SecureAcceptor {
+            handle: handle,
+            tls: Rc::new(tls),
+            metrics: metrics,
+            proxy_protocol: false,
+            idle_timeout: None,
+            idle_timer: Timer::default(),
+        }
+    }
+
+    pub fn with_proxy_protocol(mut self, enabled: bool) -> SecureAcceptor {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    /// Fails a connection that neither reads nor writes for `duration`.
+    pub fn with_idle_timeout(mut self, duration: Option<Duration>) -> SecureAcceptor {
+        self.idle_timeout = duration;
+        self
+    }
+}
+
+impl Acceptor for SecureAcceptor {
+    fn accept(&self, addr: &SocketAddr) -> io::Result<(SocketAddr, Forwarder)> {
+        let listener = TcpListener::bind(addr, &self.handle)?;
+        let local_addr = listener.local_addr()?;
+        let proxy_protocol = self.proxy_protocol;
+        let idle_timeout = self.idle_timeout;
+        let idle_timer = self.idle_timer.clone();
+        let dropped = self.metrics.clone().scope().counter("connection_proxy_protocol_error_count".into());
+        let metrics = self.metrics.clone();
+        let idle_metrics = self.metrics.clone();
+        let tls = self.tls.clone();
+        let incoming = listener.incoming()
+            .and_then(move |(io, _peer)| accept_secure(io, tls.clone(), proxy_protocol))
+            .filter_map(move |res| match res {
+                Ok(sock) => Some(sock),
+                Err(e) => {
+                    info!("dropping connection with malformed PROXY protocol header: {}", e);
+                    metrics.clone().recorder().incr(&dropped, 1);
+                    None
+                }
+            })
+            .map(move |sock| match idle_timeout {
+                Some(d) => sock.with_idle_timeout(d, idle_timer.clone(), idle_metrics.clone()),
+                None => sock,
+            });
+        Ok((local_addr, Box::new(incoming)))
+    }
+}
+
+/// Either hands the accepted stream straight to `Socket::plain`, or first reads and
+/// strips an inbound PROXY protocol header and uses its address instead of the
+/// transport's own peer address.
+fn accept_plain(io: TcpStream,
+                 proxy_protocol: bool)
+                 -> Box<Future<Item = io::Result<Socket>, Error = io::Error>> {
+    if proxy_protocol {
+        Box::new(ReadProxyHeader::new(io).then(|res| {
+            Ok(res.and_then(|(io, addrs)| {
+                let mut sock = Socket::plain(io)?;
+                if let Some(src) = addrs.src {
+                    sock.set_peer_addr(src);
+                }
+                Ok(sock)
+            }))
+        }))
+    } else {
+        Box::new(future::ok(Socket::plain(io)))
+    }
+}
+
+fn accept_secure(io: TcpStream,
+                  tls: Rc<rustls::ServerConfig>,
+                  proxy_protocol: bool)
+                  -> Box<Future<Item = io::Result<Socket>, Error = io::Error>> {
+    if proxy_protocol {
+        Box::new(ReadProxyHeader::new(io).then(move |res| {
+            Ok(res.and_then(|(io, addrs)| {
+                let session = rustls::ServerSession::new(&tls);
+                let mut sock = Socket::secure(io, Box::new(session))?;
+                if let Some(src) = addrs.src {
+                    sock.set_peer_addr(src);
+                }
+                Ok(sock)
+            }))
+        }))
+    } else {
+        let session = rustls::ServerSession::new(&tls);
+        Box::new(future::ok(Socket::secure(io, Box::new(session))))
+    }
+}
+
+/// A future that reads a PROXY protocol header one byte at a time -- since a v1 header's
+/// length isn't known until its terminating CRLF is seen -- so that no payload bytes
+/// past the header are ever consumed from the socket.
+struct ReadProxyHeader {
+    io: Option<TcpStream>,
+    buf: Vec<u8>,
+}
+
+impl ReadProxyHeader {
+    fn new(io: TcpStream) -> ReadProxyHeader {
+        ReadProxyHeader {
+            io: Some(io),
+            buf: Vec::with_capacity(MAX_HEADER_LEN),
+        }
+    }
+}
+
+impl Future for ReadProxyHeader {
+    type Item = (TcpStream, proxy_protocol::ProxyAddrs);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(TcpStream, proxy_protocol::ProxyAddrs), io::Error> {
+        loop {
+            let mut byte = [0u8; 1];
+            match self.io.as_mut().expect("polled after completion").read(&mut byte) {
+                Ok(0) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                               "eof while reading PROXY protocol header"))
+                }
+                Ok(_) => {
+                    self.buf.push(byte[0]);
+                    if let Some((addrs, _)) = proxy_protocol::decode(&self.buf)? {
+                        let io = self.io.take().unwrap();
+                        return Ok(Async::Ready((io, addrs)));
+                    }
+                    if self.buf.len() > MAX_HEADER_LEN {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                   "PROXY protocol header too long"));
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Accepts connections on a Unix domain socket, for sidecar-style deployments that
+/// front a service over a filesystem path instead of a loopback port.
+pub struct UnixAcceptor {
+    handle: Handle,
+    metrics: tacho::Metrics,
+    idle_timeout: Option<Duration>,
+    idle_timer: Timer,
+}
+
+impl UnixAcceptor {
+    pub fn new(handle: Handle, metrics: tacho::Metrics) -> UnixAcceptor {
+        UnixAcceptor {
+            handle: handle,
+            metrics: metrics,
+            idle_timeout: None,
+            idle_timer: Timer::default(),
+        }
+    }
+
+    /// Fails a connection that neither reads nor writes for `duration`.
+    pub fn with_idle_timeout(mut self, duration: Option<Duration>) -> UnixAcceptor {
+        self.idle_timeout = duration;
+        self
+    }
+
+    /// Binds `path`, producing a stream of accepted connections.
+    ///
+    /// When `owned` is set, a stale socket file left at `path` by an unclean shutdown
+    /// is removed before binding, and the path is unlinked again once the returned
+    /// stream (and the `UnixListener` backing it) is dropped.
+    pub fn accept(&self, path: &Path, owned: bool) -> io::Result<Forwarder> {
+        if owned {
+            // Ignore failures: the path may simply not exist yet.
+            let _ = fs::remove_file(path);
+        }
+        let listener = UnixListener::bind(path, &self.handle)?;
+        let guard = if owned {
+            Some(PathGuard(path.to_path_buf()))
+        } else {
+            None
+        };
+        let idle_timeout = self.idle_timeout;
+        let idle_timer = self.idle_timer.clone();
+        let idle_metrics = self.metrics.clone();
+        let incoming = listener.incoming()
+            .map(|(io, _peer)| Socket::unix(io))
+            .map(move |sock| match idle_timeout {
+                Some(d) => sock.with_idle_timeout(d, idle_timer.clone(), idle_metrics.clone()),
+                None => sock,
+            });
+        Ok(Box::new(UnixIncoming {
+            inner: Box::new(incoming),
+            _guard: guard,
+        }))
+    }
+}
+
+/// Removes the socket file at `0` when dropped.
+struct PathGuard(PathBuf);
+impl Drop for PathGuard {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Wraps the raw accepted-connection stream together with the cleanup guard for the
+/// socket file it was bound to, so the path is unlinked once the server shuts down.
+struct UnixIncoming {
+    inner: Box<Stream<Item = Socket, Error = io::Error>>,
+    _guard: Option<PathGuard>,
+}
+
+impl Stream for UnixIncoming {
+    type Item = Socket;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Socket>, io::Error> {
+        self.inner.poll()
+    }
+}
@@ -0,0 +1,252 @@
+//! Dispatches accepted connections to a weighted set of backend endpoints.
+
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use rand::{self, Rng};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use tacho;
+use tokio_core::reactor::Handle;
+
+use {Update, WeightedAddr};
+use super::connector::Connector;
+use super::duplex;
+use super::socket::Socket;
+
+type UpdatesStream = Box<Stream<Item = Vec<Update>, Error = io::Error>>;
+
+/// Tracks the set of endpoints a balancer may dispatch connections to.
+///
+/// Endpoints are updated incrementally so that a connection dispatched to an endpoint
+/// that's still present survives a discovery poll; only endpoints named by a `Remove`
+/// are ever dropped.
+#[derive(Default)]
+struct EndpointMap {
+    addrs: Vec<WeightedAddr>,
+}
+
+impl EndpointMap {
+    fn apply(&mut self, updates: Vec<Update>) {
+        for update in updates {
+            match update {
+                Update::Add(added) => {
+                    for a in added {
+                        match self.addrs.iter_mut().find(|e| e.0 == a.0) {
+                            Some(existing) => existing.1 = a.1,
+                            None => self.addrs.push(a),
+                        }
+                    }
+                }
+                Update::Remove(removed) => {
+                    self.addrs.retain(|e| !removed.contains(&e.0));
+                }
+                Update::Reweight(addr, weight) => {
+                    if let Some(existing) = self.addrs.iter_mut().find(|e| e.0 == addr) {
+                        existing.1 = weight;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Picks an endpoint at random, weighted by each endpoint's `WeightedAddr` weight.
+    fn pick(&self) -> Option<SocketAddr> {
+        if self.addrs.is_empty() {
+            return None;
+        }
+        let total: f32 = self.addrs.iter().map(|a| a.1).sum();
+        if total <= 0.0 {
+            let i = rand::thread_rng().gen_range(0, self.addrs.len());
+            return Some(self.addrs[i].0);
+        }
+        let mut target = rand::thread_rng().next_f32() * total;
+        for a in &self.addrs {
+            if target < a.1 {
+                return Some(a.0);
+            }
+            target -= a.1;
+        }
+        self.addrs.last().map(|a| a.0)
+    }
+}
+
+/// Configures a `SharedBalancer` before it starts accepting connections.
+pub struct Balancer<C> {
+    updates: UpdatesStream,
+    connector: C,
+    buf: Rc<RefCell<Vec<u8>>>,
+    metrics: tacho::Metrics,
+}
+
+impl<C: Connector + Clone + 'static> Balancer<C> {
+    pub fn new<S>(updates: S, connector: C, buf: Rc<RefCell<Vec<u8>>>, metrics: tacho::Metrics) -> Balancer<C>
+        where S: Stream<Item = Vec<Update>, Error = io::Error> + 'static
+    {
+        Balancer {
+            updates: Box::new(updates),
+            connector: connector,
+            buf: buf,
+            metrics: metrics,
+        }
+    }
+
+    /// Spawns the task that keeps the endpoint set up to date and returns a cloneable
+    /// sink that accepted connections are forwarded into.
+    pub fn into_shared(self, max_waiters: usize, handle: Handle) -> SharedBalancer<C> {
+        let inner = Rc::new(RefCell::new(Inner {
+            endpoints: EndpointMap::default(),
+            waiters: VecDeque::new(),
+            max_waiters: max_waiters,
+            connector: self.connector,
+            buf: self.buf,
+            metrics: self.metrics,
+        }));
+
+        let driving = inner.clone();
+        let driving_handle = handle.clone();
+        let drive = self.updates
+            .for_each(move |updates| {
+                let mut inner = driving.borrow_mut();
+                inner.endpoints.apply(updates);
+                inner.drain_waiters(&driving_handle);
+                Ok(())
+            })
+            .map_err(|e| error!("discovery stream failed: {}", e));
+        handle.spawn(drive);
+
+        SharedBalancer {
+            inner: inner,
+            handle: handle,
+        }
+    }
+}
+
+struct Inner<C> {
+    endpoints: EndpointMap,
+    waiters: VecDeque<Socket>,
+    max_waiters: usize,
+    connector: C,
+    buf: Rc<RefCell<Vec<u8>>>,
+    metrics: tacho::Metrics,
+}
+
+impl<C: Connector + Clone + 'static> Inner<C> {
+    fn dispatch(&self, sock: Socket, handle: &Handle) {
+        let addr = match self.endpoints.pick() {
+            Some(addr) => addr,
+            None => return,
+        };
+        let src = sock.peer_addr();
+        let buf = self.buf.clone();
+        let dropped = self.metrics.clone().scope().counter("connection_dial_error_count".into());
+        let metrics = self.metrics.clone();
+        let connecting = self.connector
+            .connect(&addr, Some(src))
+            .and_then(move |dst| duplex::copy(sock, dst, buf))
+            .map_err(move |e| {
+                metrics.clone().recorder().incr(&dropped, 1);
+                debug!("proxying connection from {} failed: {}", src, e);
+            });
+        handle.spawn(connecting);
+    }
+
+    /// Once new endpoints arrive, give any connections that had been queued waiting for
+    /// one a chance to be dispatched.
+    fn drain_waiters(&mut self, handle: &Handle) {
+        while !self.waiters.is_empty() && self.endpoints.pick().is_some() {
+            let sock = self.waiters.pop_front().unwrap();
+            self.dispatch(sock, handle);
+        }
+    }
+}
+
+/// A cloneable handle to a `Balancer`'s shared state; each clone may be forwarded
+/// connections from a different server.
+pub struct SharedBalancer<C> {
+    inner: Rc<RefCell<Inner<C>>>,
+    handle: Handle,
+}
+
+impl<C> Clone for SharedBalancer<C> {
+    fn clone(&self) -> SharedBalancer<C> {
+        SharedBalancer {
+            inner: self.inner.clone(),
+            handle: self.handle.clone(),
+        }
+    }
+}
+
+impl<C: Connector + Clone + 'static> Sink for SharedBalancer<C> {
+    type SinkItem = Socket;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, sock: Socket) -> StartSend<Socket, io::Error> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.endpoints.pick().is_some() {
+            inner.dispatch(sock, &self.handle);
+        } else if inner.waiters.len() < inner.max_waiters {
+            inner.waiters.push_back(sock);
+        } else {
+            debug!("dropping connection from {}: no endpoints available", sock.peer_addr());
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn add_inserts_new_and_updates_existing() {
+        let mut m = EndpointMap::default();
+        m.apply(vec![Update::Add(vec![WeightedAddr(addr("10.0.0.1:80"), 1.0)])]);
+        assert_eq!(m.addrs, vec![WeightedAddr(addr("10.0.0.1:80"), 1.0)]);
+
+        m.apply(vec![Update::Add(vec![WeightedAddr(addr("10.0.0.1:80"), 2.0)])]);
+        assert_eq!(m.addrs, vec![WeightedAddr(addr("10.0.0.1:80"), 2.0)]);
+    }
+
+    #[test]
+    fn remove_drops_only_named_endpoints() {
+        let mut m = EndpointMap::default();
+        m.apply(vec![Update::Add(vec![WeightedAddr(addr("10.0.0.1:80"), 1.0),
+                                       WeightedAddr(addr("10.0.0.2:80"), 1.0)])]);
+        m.apply(vec![Update::Remove(vec![addr("10.0.0.1:80")])]);
+        assert_eq!(m.addrs, vec![WeightedAddr(addr("10.0.0.2:80"), 1.0)]);
+    }
+
+    #[test]
+    fn reweight_updates_existing_endpoint_only() {
+        let mut m = EndpointMap::default();
+        m.apply(vec![Update::Add(vec![WeightedAddr(addr("10.0.0.1:80"), 1.0)])]);
+        m.apply(vec![Update::Reweight(addr("10.0.0.2:80"), 5.0)]);
+        assert_eq!(m.addrs, vec![WeightedAddr(addr("10.0.0.1:80"), 1.0)]);
+
+        m.apply(vec![Update::Reweight(addr("10.0.0.1:80"), 5.0)]);
+        assert_eq!(m.addrs, vec![WeightedAddr(addr("10.0.0.1:80"), 5.0)]);
+    }
+
+    #[test]
+    fn pick_returns_none_when_empty() {
+        let m = EndpointMap::default();
+        assert_eq!(m.pick(), None);
+    }
+
+    #[test]
+    fn pick_returns_the_sole_endpoint() {
+        let mut m = EndpointMap::default();
+        m.apply(vec![Update::Add(vec![WeightedAddr(addr("10.0.0.1:80"), 1.0)])]);
+        assert_eq!(m.pick(), Some(addr("10.0.0.1:80")));
+    }
+}
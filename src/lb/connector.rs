@@ -0,0 +1,122 @@
+//! Dials backend connections for the balancer.
+
+use futures::{Future, future};
+use rustls;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_io::io::write_all;
+
+use super::proxy_protocol;
+use super::socket::Socket;
+
+/// Dials a backend address, producing a connected `Socket`.
+pub trait Connector {
+    type Future: Future<Item = Socket, Error = io::Error>;
+
+    /// `src` is the address of the client whose accepted connection is being
+    /// forwarded, if known; implementations that emit a PROXY protocol header use it
+    /// as the header's source address.
+    fn connect(&self, addr: &SocketAddr, src: Option<SocketAddr>) -> Self::Future;
+}
+
+#[derive(Clone)]
+pub struct PlainConnector {
+    handle: Handle,
+    proxy_protocol: bool,
+}
+
+impl PlainConnector {
+    pub fn new(handle: Handle) -> PlainConnector {
+        PlainConnector {
+            handle: handle,
+            proxy_protocol: false,
+        }
+    }
+
+    /// Enables writing a PROXY protocol v2 header to every dialed connection before any
+    /// application bytes.
+    pub fn with_proxy_protocol(mut self, enabled: bool) -> PlainConnector {
+        self.proxy_protocol = enabled;
+        self
+    }
+}
+
+impl Connector for PlainConnector {
+    type Future = Box<Future<Item = Socket, Error = io::Error>>;
+
+    fn connect(&self, addr: &SocketAddr, src: Option<SocketAddr>) -> Self::Future {
+        let proxy_protocol = self.proxy_protocol;
+        let addr = *addr;
+        let f = TcpStream::connect(&addr, &self.handle)
+            .and_then(Socket::plain)
+            .and_then(move |sock| write_proxy_header(sock, proxy_protocol, src, addr));
+        Box::new(f)
+    }
+}
+
+#[derive(Clone)]
+pub struct SecureConnector {
+    dns_name: String,
+    tls: Arc<rustls::ClientConfig>,
+    handle: Handle,
+    proxy_protocol: bool,
+}
+
+impl SecureConnector {
+    pub fn new(dns_name: String, tls: rustls::ClientConfig, handle: Handle) -> SecureConnector {
+        SecureConnector {
+            dns_name: dns_name,
+            tls: Arc::new(tls),
+            handle: handle,
+            proxy_protocol: false,
+        }
+    }
+
+    pub fn with_proxy_protocol(mut self, enabled: bool) -> SecureConnector {
+        self.proxy_protocol = enabled;
+        self
+    }
+}
+
+impl Connector for SecureConnector {
+    type Future = Box<Future<Item = Socket, Error = io::Error>>;
+
+    fn connect(&self, addr: &SocketAddr, src: Option<SocketAddr>) -> Self::Future {
+        let proxy_protocol = self.proxy_protocol;
+        let addr = *addr;
+        let tls = self.tls.clone();
+        let dns_name = self.dns_name.clone();
+        let f = TcpStream::connect(&addr, &self.handle)
+            .and_then(move |io| {
+                let session = rustls::ClientSession::new(&tls, &dns_name);
+                Socket::secure(io, Box::new(session))
+            })
+            .and_then(move |sock| write_proxy_header(sock, proxy_protocol, src, addr));
+        Box::new(f)
+    }
+}
+
+/// Writes the (unambiguous, binary) PROXY protocol v2 header for a connection accepted
+/// from `src` before any application bytes are sent, or passes `sock` through unchanged
+/// if proxy protocol is disabled or `src` is unknown.
+///
+/// Driven through `tokio_io::io::write_all` rather than a direct `Write::write_all` call,
+/// since `Socket` is non-blocking and a blocking-style write can return `WouldBlock`,
+/// which would otherwise bubble up through `connect()`'s `and_then` and be mistaken for a
+/// dial failure.
+fn write_proxy_header(sock: Socket,
+                       enabled: bool,
+                       src: Option<SocketAddr>,
+                       dst: SocketAddr)
+                       -> Box<Future<Item = Socket, Error = io::Error>> {
+    match (enabled, src) {
+        (true, Some(src)) => {
+            let header = proxy_protocol::encode_v2(src, dst);
+            Box::new(write_all(sock, header).map(|(sock, _)| sock))
+        }
+        _ => Box::new(future::ok(sock)),
+    }
+}
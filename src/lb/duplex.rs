@@ -0,0 +1,108 @@
+//! Copies bytes in both directions between two connected sockets.
+
+use futures::{Async, Future, Poll};
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::cell::RefCell;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Proxies bytes between `a` and `b` until both directions have seen EOF (each EOF is
+/// passed on as a write shutdown of the other side) or either side errors. Each
+/// direction gets its own buffer, sized to match the shared transfer buffer (the
+/// configured `buffer_size`).
+pub fn copy<A, B>(a: A, b: B, buf: Rc<RefCell<Vec<u8>>>) -> Duplex<A, B>
+    where A: AsyncRead + AsyncWrite,
+          B: AsyncRead + AsyncWrite
+{
+    let sz = buf.borrow().len();
+    Duplex {
+        a: a,
+        b: b,
+        a_to_b: HalfDuplex::new(vec![0; sz]),
+        b_to_a: HalfDuplex::new(vec![0; sz]),
+    }
+}
+
+pub struct Duplex<A, B> {
+    a: A,
+    b: B,
+    a_to_b: HalfDuplex,
+    b_to_a: HalfDuplex,
+}
+
+impl<A, B> Future for Duplex<A, B>
+    where A: AsyncRead + AsyncWrite,
+          B: AsyncRead + AsyncWrite
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        let a_done = self.a_to_b.poll_copy(&mut self.a, &mut self.b)?;
+        let b_done = self.b_to_a.poll_copy(&mut self.b, &mut self.a)?;
+        if a_done && b_done {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// The state of copying bytes read from one side into the other.
+struct HalfDuplex {
+    buf: Vec<u8>,
+    pos: usize,
+    cap: usize,
+    read_done: bool,
+}
+
+impl HalfDuplex {
+    fn new(buf: Vec<u8>) -> HalfDuplex {
+        HalfDuplex {
+            buf: buf,
+            pos: 0,
+            cap: 0,
+            read_done: false,
+        }
+    }
+
+    /// Drives one direction of the duplex; returns `Ok(true)` once that direction has
+    /// seen EOF and flushed all buffered bytes to the destination.
+    fn poll_copy<R, W>(&mut self, reader: &mut R, writer: &mut W) -> io::Result<bool>
+        where R: Read,
+              W: Write + AsyncWrite
+    {
+        loop {
+            if self.pos == self.cap && !self.read_done {
+                match reader.read(&mut self.buf) {
+                    Ok(0) => self.read_done = true,
+                    Ok(n) => {
+                        self.pos = 0;
+                        self.cap = n;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                    Err(e) => return Err(e),
+                }
+            }
+
+            while self.pos < self.cap {
+                match writer.write(&self.buf[self.pos..self.cap]) {
+                    Ok(0) => {
+                        return Err(io::Error::new(io::ErrorKind::WriteZero, "write returned 0"))
+                    }
+                    Ok(n) => self.pos += n,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if self.read_done {
+                match writer.shutdown() {
+                    Ok(Async::Ready(())) => return Ok(true),
+                    Ok(Async::NotReady) => return Ok(false),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Tears down connections that stop making progress, guarding against slow-loris
+//! clients and leaked half-open connections.
+
+use futures::{Async, Future};
+use std::io;
+use std::time::Duration;
+use tacho;
+use tokio_timer::{Sleep, Timer};
+
+/// Tracks the deadline for a single duplex connection: armed on construction, reset on
+/// every successful read or write, and consulted before each one. Once the deadline
+/// elapses without activity, every subsequent check fails the connection.
+pub struct IdleGuard {
+    timer: Timer,
+    duration: Duration,
+    sleep: Sleep,
+    metrics: tacho::Metrics,
+    timeout_count: tacho::CounterKey,
+}
+
+impl IdleGuard {
+    /// `timer` should be a `Timer` shared across every connection on the same acceptor:
+    /// each `Timer` owns a background wheel thread, so building one per connection would
+    /// turn a guard against resource exhaustion into a thread-per-connection leak of its
+    /// own.
+    pub fn new(duration: Duration, timer: Timer, metrics: tacho::Metrics) -> IdleGuard {
+        let sleep = timer.sleep(duration);
+        IdleGuard {
+            timer: timer,
+            duration: duration,
+            sleep: sleep,
+            timeout_count: metrics.scope().counter("connection_idle_timeout_count".into()),
+            metrics: metrics,
+        }
+    }
+
+    /// Returns an error if the deadline has elapsed since the last reset.
+    pub fn check(&mut self) -> io::Result<()> {
+        match self.sleep.poll() {
+            Ok(Async::Ready(())) => {
+                self.metrics.recorder().incr(&self.timeout_count, 1);
+                Err(io::Error::new(io::ErrorKind::TimedOut, "connection idle timeout"))
+            }
+            Ok(Async::NotReady) => Ok(()),
+            Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
+        }
+    }
+
+    /// Pushes the deadline back out by the configured duration.
+    pub fn reset(&mut self) {
+        self.sleep = self.timer.sleep(self.duration);
+    }
+}
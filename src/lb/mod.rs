@@ -0,0 +1,14 @@
+//! Accepts, balances, and forwards layer-4 connections.
+
+mod acceptor;
+mod balancer;
+mod connector;
+mod duplex;
+mod idle;
+mod proxy_protocol;
+mod socket;
+
+pub use self::acceptor::{Acceptor, Forwarder, PlainAcceptor, SecureAcceptor, UnixAcceptor};
+pub use self::balancer::{Balancer, SharedBalancer};
+pub use self::connector::{Connector, PlainConnector, SecureConnector};
+pub use self::socket::Socket;
@@ -0,0 +1,299 @@
+//! Encoding and decoding of HAProxy's PROXY protocol (v1 and v2) headers.
+//!
+//! See http://www.haproxy.org/download/1.8/doc/proxy-protocol.txt
+
+use byteorder::{BigEndian, ByteOrder};
+use std::io;
+use std::net::{self, IpAddr, SocketAddr};
+
+/// The fixed 12-byte signature that opens every v2 header.
+const V2_SIGNATURE: [u8; 12] = [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54,
+                                 0x0A];
+
+/// A v2 header is never larger than its 16-byte fixed portion plus a 36-byte TCP6
+/// address block.
+const V2_MAX_LEN: usize = 16 + 36;
+
+/// A v1 header is a single CRLF-terminated ASCII line of at most 107 bytes.
+pub const V1_MAX_LEN: usize = 107;
+
+/// Protocol version/command byte for "PROXY" (as opposed to "LOCAL").
+const V2_CMD_PROXY: u8 = 0x21;
+
+/// Address family/transport byte for TCP over IPv4.
+const V2_FAM_TCP4: u8 = 0x11;
+
+/// Address family/transport byte for TCP over IPv6.
+const V2_FAM_TCP6: u8 = 0x21;
+
+/// The addresses carried by a decoded PROXY protocol header.
+///
+/// `None` means the header was well-formed but declined to carry an address (v1's
+/// `UNKNOWN`), in which case the connection's own transport-level peer address should
+/// be used instead.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProxyAddrs {
+    pub src: Option<SocketAddr>,
+    pub dst: Option<SocketAddr>,
+}
+
+/// Builds the PROXY protocol v2 binary header carrying `src` as the source address and
+/// `dst` as the destination address (connectors pass the backend address they dialed).
+///
+/// v2 is emitted (rather than v1) by connectors because it is unambiguous to parse and
+/// does not require scanning for a terminator.
+pub fn encode_v2(src: SocketAddr, dst: SocketAddr) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(V2_MAX_LEN);
+    buf.extend_from_slice(&V2_SIGNATURE);
+    buf.push(V2_CMD_PROXY);
+    match (src, dst) {
+        (SocketAddr::V4(src), SocketAddr::V4(dst)) => {
+            buf.push(V2_FAM_TCP4);
+            let mut len = [0; 2];
+            BigEndian::write_u16(&mut len, 12);
+            buf.extend_from_slice(&len);
+            buf.extend_from_slice(&src.ip().octets());
+            buf.extend_from_slice(&dst.ip().octets());
+            let mut ports = [0; 4];
+            BigEndian::write_u16(&mut ports[0..2], src.port());
+            BigEndian::write_u16(&mut ports[2..4], dst.port());
+            buf.extend_from_slice(&ports);
+        }
+        (SocketAddr::V6(src), SocketAddr::V6(dst)) => {
+            buf.push(V2_FAM_TCP6);
+            let mut len = [0; 2];
+            BigEndian::write_u16(&mut len, 36);
+            buf.extend_from_slice(&len);
+            buf.extend_from_slice(&src.ip().octets());
+            buf.extend_from_slice(&dst.ip().octets());
+            let mut ports = [0; 4];
+            BigEndian::write_u16(&mut ports[0..2], src.port());
+            BigEndian::write_u16(&mut ports[2..4], dst.port());
+            buf.extend_from_slice(&ports);
+        }
+        // A mixed v4/v6 src/dst pair (e.g. an IPv6 client proxied to an IPv4 backend)
+        // has no v2 address family; fall back to an unspecified-family header with no
+        // address block rather than guessing.
+        _ => {
+            buf.push(0x00);
+            buf.extend_from_slice(&[0, 0]);
+        }
+    }
+    buf
+}
+
+/// Attempts to decode a PROXY protocol header (v1 or v2) from the front of `buf`.
+///
+/// Returns the decoded addresses and the number of bytes consumed, or `Ok(None)` if
+/// `buf` does not yet contain a complete header but could still become one (the caller
+/// should buffer more bytes and retry). Returns `Err` as soon as the header is known to
+/// be malformed.
+pub fn decode(buf: &[u8]) -> io::Result<Option<(ProxyAddrs, usize)>> {
+    if buf.len() >= V2_SIGNATURE.len() && buf[..V2_SIGNATURE.len()] == V2_SIGNATURE {
+        decode_v2(buf)
+    } else if buf.starts_with(b"PROXY ") || (buf.len() < 6 && b"PROXY "[..buf.len()].starts_with(buf)) {
+        decode_v1(buf)
+    } else if buf.len() < V2_SIGNATURE.len() && V2_SIGNATURE.starts_with(buf) {
+        // Not enough bytes yet to see the whole v2 signature.
+        Ok(None)
+    } else {
+        Err(malformed("unrecognized PROXY protocol signature"))
+    }
+}
+
+fn decode_v2(buf: &[u8]) -> io::Result<Option<(ProxyAddrs, usize)>> {
+    const HEADER_LEN: usize = 16;
+    if buf.len() < HEADER_LEN {
+        return Ok(None);
+    }
+    let cmd = buf[12] & 0x0F;
+    let ver = buf[12] >> 4;
+    if ver != 2 {
+        return Err(malformed("unsupported PROXY protocol version"));
+    }
+    let fam = buf[13];
+    let addr_len = BigEndian::read_u16(&buf[14..16]) as usize;
+    let total = HEADER_LEN + addr_len;
+    if total > V2_MAX_LEN {
+        return Err(malformed("PROXY v2 address block too large"));
+    }
+    if buf.len() < total {
+        return Ok(None);
+    }
+    if cmd != (V2_CMD_PROXY & 0x0F) {
+        // LOCAL connections (e.g. health checks) carry no meaningful address.
+        return Err(malformed("PROXY v2 LOCAL command is not supported"));
+    }
+    let block = &buf[HEADER_LEN..total];
+    let addrs = match fam {
+        f if f == V2_FAM_TCP4 => {
+            if block.len() < 12 {
+                return Err(malformed("PROXY v2 TCP4 address block too short"));
+            }
+            let src_ip = net::Ipv4Addr::new(block[0], block[1], block[2], block[3]);
+            let dst_ip = net::Ipv4Addr::new(block[4], block[5], block[6], block[7]);
+            let src_port = BigEndian::read_u16(&block[8..10]);
+            let dst_port = BigEndian::read_u16(&block[10..12]);
+            ProxyAddrs {
+                src: Some(SocketAddr::new(IpAddr::V4(src_ip), src_port)),
+                dst: Some(SocketAddr::new(IpAddr::V4(dst_ip), dst_port)),
+            }
+        }
+        f if f == V2_FAM_TCP6 => {
+            if block.len() < 36 {
+                return Err(malformed("PROXY v2 TCP6 address block too short"));
+            }
+            let mut src_octets = [0u8; 16];
+            src_octets.copy_from_slice(&block[0..16]);
+            let mut dst_octets = [0u8; 16];
+            dst_octets.copy_from_slice(&block[16..32]);
+            let src_port = BigEndian::read_u16(&block[32..34]);
+            let dst_port = BigEndian::read_u16(&block[34..36]);
+            ProxyAddrs {
+                src: Some(SocketAddr::new(IpAddr::V6(net::Ipv6Addr::from(src_octets)), src_port)),
+                dst: Some(SocketAddr::new(IpAddr::V6(net::Ipv6Addr::from(dst_octets)), dst_port)),
+            }
+        }
+        _ => return Err(malformed("unsupported PROXY v2 address family")),
+    };
+    Ok(Some((addrs, total)))
+}
+
+fn decode_v1(buf: &[u8]) -> io::Result<Option<(ProxyAddrs, usize)>> {
+    let limit = ::std::cmp::min(buf.len(), V1_MAX_LEN);
+    let line_end = match buf[..limit].windows(2).position(|w| w == b"\r\n") {
+        Some(i) => i,
+        None if buf.len() >= V1_MAX_LEN => return Err(malformed("PROXY v1 header too long")),
+        None => return Ok(None),
+    };
+    let line = ::std::str::from_utf8(&buf[..line_end]).map_err(|_| malformed("PROXY v1 header is not ascii"))?;
+    let mut parts = line.split(' ');
+    if parts.next() != Some("PROXY") {
+        return Err(malformed("expected PROXY v1 header"));
+    }
+    let proto = parts.next().ok_or_else(|| malformed("missing PROXY v1 protocol"))?;
+    if proto == "UNKNOWN" {
+        // No usable address; the caller falls back to the connection's real peer address.
+        return Ok(Some((ProxyAddrs { src: None, dst: None }, line_end + 2)));
+    }
+    let src_ip: IpAddr = parts.next()
+        .ok_or_else(|| malformed("missing PROXY v1 src ip"))?
+        .parse()
+        .map_err(|_| malformed("invalid PROXY v1 src ip"))?;
+    let dst_ip: IpAddr = parts.next()
+        .ok_or_else(|| malformed("missing PROXY v1 dst ip"))?
+        .parse()
+        .map_err(|_| malformed("invalid PROXY v1 dst ip"))?;
+    let src_port: u16 = parts.next()
+        .ok_or_else(|| malformed("missing PROXY v1 src port"))?
+        .parse()
+        .map_err(|_| malformed("invalid PROXY v1 src port"))?;
+    let dst_port: u16 = parts.next()
+        .ok_or_else(|| malformed("missing PROXY v1 dst port"))?
+        .parse()
+        .map_err(|_| malformed("invalid PROXY v1 dst port"))?;
+    Ok(Some((ProxyAddrs {
+        src: Some(SocketAddr::new(src_ip, src_port)),
+        dst: Some(SocketAddr::new(dst_ip, dst_port)),
+    }, line_end + 2)))
+}
+
+fn malformed(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("malformed PROXY protocol header: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn v2_round_trips_tcp4() {
+        let src = addr("1.2.3.4:1111");
+        let dst = addr("5.6.7.8:2222");
+        let header = encode_v2(src, dst);
+        let (addrs, consumed) = decode(&header).unwrap().unwrap();
+        assert_eq!(consumed, header.len());
+        assert_eq!(addrs.src, Some(src));
+        assert_eq!(addrs.dst, Some(dst));
+    }
+
+    #[test]
+    fn v2_round_trips_tcp6() {
+        let src = addr("[::1]:1111");
+        let dst = addr("[::2]:2222");
+        let header = encode_v2(src, dst);
+        let (addrs, consumed) = decode(&header).unwrap().unwrap();
+        assert_eq!(consumed, header.len());
+        assert_eq!(addrs.src, Some(src));
+        assert_eq!(addrs.dst, Some(dst));
+    }
+
+    #[test]
+    fn v2_incomplete_header_asks_for_more_bytes() {
+        const PREFIX_LEN: usize = 10;
+        let header = encode_v2(addr("1.2.3.4:1111"), addr("5.6.7.8:2222"));
+        assert_eq!(decode(&header[..PREFIX_LEN]).unwrap(), None);
+    }
+
+    #[test]
+    fn v2_rejects_local_command() {
+        let mut header = encode_v2(addr("1.2.3.4:1111"), addr("5.6.7.8:2222"));
+        header[12] = 0x20; // version 2, command LOCAL
+        assert!(decode(&header).is_err());
+    }
+
+    #[test]
+    fn v1_parses_tcp4_line() {
+        let line = b"PROXY TCP4 1.2.3.4 5.6.7.8 1111 2222\r\nextra";
+        let (addrs, consumed) = decode(line).unwrap().unwrap();
+        assert_eq!(consumed, line.len() - "extra".len());
+        assert_eq!(addrs.src, Some(addr("1.2.3.4:1111")));
+        assert_eq!(addrs.dst, Some(addr("5.6.7.8:2222")));
+    }
+
+    #[test]
+    fn v1_parses_full_length_tcp6_line() {
+        let line = b"PROXY TCP6 ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff \
+                      ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff 65535 65535\r\n";
+        assert!(line.len() <= V1_MAX_LEN, "fixture must stay within the v1 limit");
+        let (addrs, consumed) = decode(line).unwrap().unwrap();
+        assert_eq!(consumed, line.len());
+        assert!(addrs.src.is_some());
+        assert!(addrs.dst.is_some());
+    }
+
+    #[test]
+    fn v1_unknown_decodes_with_no_addresses() {
+        let line = b"PROXY UNKNOWN\r\n";
+        let (addrs, consumed) = decode(line).unwrap().unwrap();
+        assert_eq!(consumed, line.len());
+        assert_eq!(addrs.src, None);
+        assert_eq!(addrs.dst, None);
+    }
+
+    #[test]
+    fn v1_waits_for_terminator() {
+        assert_eq!(decode(b"PROXY TCP4 1.2.3.4").unwrap(), None);
+    }
+
+    #[test]
+    fn v1_too_long_without_terminator_is_malformed() {
+        let mut line = b"PROXY TCP4 ".to_vec();
+        line.extend(vec![b'1'; V1_MAX_LEN]);
+        assert!(decode(&line).is_err());
+    }
+
+    #[test]
+    fn unrecognized_signature_is_malformed() {
+        assert!(decode(b"GARBAGE\0\0\0\0\0\0").is_err());
+    }
+
+    #[test]
+    fn short_buffer_waits_for_more_bytes() {
+        assert_eq!(decode(b"PR").unwrap(), None);
+    }
+}
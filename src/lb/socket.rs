@@ -0,0 +1,183 @@
+//! A transport-agnostic duplex socket, plain or behind TLS.
+
+use futures::Poll;
+use rustls;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tacho;
+use tokio_core::net::TcpStream;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Timer;
+use tokio_uds::UnixStream;
+
+use super::idle::IdleGuard;
+
+enum Transport {
+    Plain(TcpStream),
+    Secure(TlsStream),
+    Unix(UnixStream),
+}
+
+/// A connected socket accepted or dialed by the balancer.
+///
+/// `peer_addr` normally mirrors the underlying transport's peer address, but an
+/// acceptor that terminates an inbound PROXY protocol header overrides it with the
+/// address the header claims, so the balancer (and any PROXY header a connector writes
+/// to the backend) sees the true client. No metric is labeled with this address.
+pub struct Socket {
+    transport: Transport,
+    peer_addr: SocketAddr,
+    idle: Option<IdleGuard>,
+}
+
+impl Socket {
+    pub fn plain(io: TcpStream) -> io::Result<Socket> {
+        let peer_addr = io.peer_addr()?;
+        Ok(Socket {
+            transport: Transport::Plain(io),
+            peer_addr: peer_addr,
+            idle: None,
+        })
+    }
+
+    pub fn secure(io: TcpStream, session: Box<rustls::Session>) -> io::Result<Socket> {
+        let peer_addr = io.peer_addr()?;
+        Ok(Socket {
+            transport: Transport::Secure(TlsStream::new(io, session)),
+            peer_addr: peer_addr,
+            idle: None,
+        })
+    }
+
+    /// Unix domain sockets have no IP peer address; connections accepted on one report
+    /// an unspecified address instead.
+    pub fn unix(io: UnixStream) -> Socket {
+        Socket {
+            transport: Transport::Unix(io),
+            peer_addr: "0.0.0.0:0".parse().unwrap(),
+            idle: None,
+        }
+    }
+
+    /// Overrides the address reported by `peer_addr`, as recovered from a PROXY
+    /// protocol header.
+    pub fn set_peer_addr(&mut self, addr: SocketAddr) {
+        self.peer_addr = addr;
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Arms an idle timeout: if neither a read nor a write completes within `duration`,
+    /// the connection is failed so both halves of the proxied duplex can be closed.
+    ///
+    /// `timer` is shared across every connection on the acceptor rather than built here,
+    /// since a `Timer` owns a background wheel thread and we don't want one per socket.
+    pub fn with_idle_timeout(mut self, duration: Duration, timer: Timer, metrics: tacho::Metrics) -> Socket {
+        self.idle = Some(IdleGuard::new(duration, timer, metrics));
+        self
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(ref mut idle) = self.idle {
+            idle.check()?;
+        }
+        let n = match self.transport {
+            Transport::Plain(ref mut s) => s.read(buf),
+            Transport::Secure(ref mut s) => s.read(buf),
+            Transport::Unix(ref mut s) => s.read(buf),
+        }?;
+        if let Some(ref mut idle) = self.idle {
+            idle.reset();
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(ref mut idle) = self.idle {
+            idle.check()?;
+        }
+        let n = match self.transport {
+            Transport::Plain(ref mut s) => s.write(buf),
+            Transport::Secure(ref mut s) => s.write(buf),
+            Transport::Unix(ref mut s) => s.write(buf),
+        }?;
+        if let Some(ref mut idle) = self.idle {
+            idle.reset();
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.transport {
+            Transport::Plain(ref mut s) => s.flush(),
+            Transport::Secure(ref mut s) => s.flush(),
+            Transport::Unix(ref mut s) => s.flush(),
+        }
+    }
+}
+
+impl AsyncRead for Socket {}
+
+impl AsyncWrite for Socket {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self.transport {
+            Transport::Plain(ref mut s) => AsyncWrite::shutdown(s),
+            Transport::Secure(ref mut s) => AsyncWrite::shutdown(&mut s.io),
+            Transport::Unix(ref mut s) => AsyncWrite::shutdown(s),
+        }
+    }
+}
+
+/// A TCP stream driving a rustls session.
+struct TlsStream {
+    io: TcpStream,
+    session: Box<rustls::Session>,
+}
+
+impl TlsStream {
+    fn new(io: TcpStream, session: Box<rustls::Session>) -> TlsStream {
+        TlsStream {
+            io: io,
+            session: session,
+        }
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.session.wants_read() {
+                self.session.read_tls(&mut self.io)?;
+                self.session
+                    .process_new_packets()
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            }
+            match self.session.read(buf) {
+                Ok(0) if self.session.wants_read() => continue,
+                res => return res,
+            }
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.session.write(buf)?;
+        self.flush()?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        while self.session.wants_write() {
+            self.session.write_tls(&mut self.io)?;
+        }
+        self.io.flush()
+    }
+}
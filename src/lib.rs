@@ -5,6 +5,7 @@
 //!
 //! Copyright 2017 Buoyant, Inc.
 
+extern crate byteorder;
 extern crate bytes;
 #[macro_use]
 extern crate log;
@@ -22,15 +23,32 @@ extern crate tokio_core;
 #[macro_use]
 extern crate tokio_io;
 extern crate tokio_timer;
+extern crate tokio_uds;
+extern crate trust_dns_resolver;
 extern crate url;
 
 use std::net;
 
 pub mod app;
+pub mod discovery;
+pub mod dns;
 pub mod lb;
 pub mod namerd;
 
 pub use lb::Balancer;
 
-#[derive(Clone, Debug)]
-pub struct WeightedAddr(pub net::SocketAddr, pub f32);
\ No newline at end of file
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeightedAddr(pub net::SocketAddr, pub f32);
+
+/// An incremental change to a balancer's endpoint set.
+///
+/// Discovery backends hand over complete address-set snapshots; diffing consecutive
+/// snapshots into `Update`s lets a `Balancer` keep connections to endpoints that are
+/// still present rather than tearing down and rebuilding its whole endpoint set on
+/// every poll.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Update {
+    Add(Vec<WeightedAddr>),
+    Remove(Vec<net::SocketAddr>),
+    Reweight(net::SocketAddr, f32),
+}
\ No newline at end of file
@@ -13,11 +13,12 @@ use tacho::{self, Timing};
 use tokio_timer::Timer;
 use url::Url;
 
+use discovery::{AddrsStream, Resolver};
+
 #[derive(Debug)]
 pub struct NamerdError(String);
 
 type AddrsFuture = Box<Future<Item = Option<Vec<::WeightedAddr>>, Error = ()>>;
-type AddrsStream = Box<Stream<Item = Vec<::WeightedAddr>, Error = ()>>;
 
 #[derive(Clone)]
 struct Stats {
@@ -38,33 +39,57 @@ impl Stats {
     }
 }
 
-/// Make a Resolver that periodically polls namerd to resolve a name
-/// to a set of addresses.
-///
-/// The returned stream never completes.
-pub fn resolve<C>(addr: net::SocketAddr,
-                  client: Client<C>,
-                  period: time::Duration,
-                  namespace: &str,
-                  target: &str,
-                  metrics: tacho::Metrics)
-                  -> AddrsStream
+/// Resolves a name to a set of addresses by periodically polling namerd's HTTP
+/// resolution API.
+pub struct NamerdResolver<C> {
+    addr: net::SocketAddr,
+    client: Client<C>,
+    period: time::Duration,
+    namespace: String,
+    target: String,
+    metrics: tacho::Metrics,
+}
+
+impl<C> NamerdResolver<C>
+    where C: Connect
+{
+    pub fn new(addr: net::SocketAddr,
+               client: Client<C>,
+               period: time::Duration,
+               namespace: &str,
+               target: &str,
+               metrics: tacho::Metrics)
+               -> NamerdResolver<C> {
+        NamerdResolver {
+            addr: addr,
+            client: client,
+            period: period,
+            namespace: namespace.into(),
+            target: target.into(),
+            metrics: metrics,
+        }
+    }
+}
+
+impl<C> Resolver for NamerdResolver<C>
     where C: Connect
 {
-    let url = {
-        let base = format!("http://{}:{}/api/1/resolve/{}",
-                           addr.ip(),
-                           addr.port().to_string(),
-                           namespace);
-        Url::parse_with_params(&base, &[("path", &target)]).unwrap()
-    };
-    let stats = Stats::new(metrics);
-    let client = Rc::new(client);
-    let init = request(client.clone(), url.clone(), stats.clone());
-    let updates = Timer::default()
-        .interval(period)
-        .then(move |_| request(client.clone(), url.clone(), stats.clone()));
-    Box::new(init.into_stream().chain(updates).filter_map(|opt| opt))
+    fn resolve(self) -> AddrsStream {
+        let url = {
+            let base = format!("http://{}:{}/api/1/resolve/{}",
+                               self.addr.ip(),
+                               self.addr.port().to_string(),
+                               self.namespace);
+            Url::parse_with_params(&base, &[("path", &self.target)]).unwrap()
+        };
+        let stats = Stats::new(self.metrics);
+        let client = Rc::new(self.client);
+        let init = request(client.clone(), url.clone(), stats.clone());
+        let updates = Timer::default()
+            .interval(self.period)
+            .then(move |_| request(client.clone(), url.clone(), stats.clone()));
+        Box::new(init.into_stream().chain(updates).filter_map(|opt| opt))
+    }
 }
 
 
@@ -131,13 +156,24 @@ fn to_buf(chunks: &[Chunk]) -> Bytes {
     buf.freeze()
 }
 
+/// Parses a namerd response body into a weighted address set, or `None` if the response
+/// carries no usable signal at all.
+///
+/// A "bound" response is the only one that explicitly names a (possibly empty) address
+/// set; its `addrs` are trusted as-is, including when empty, so that a namerd response
+/// reporting that every backend is down actually removes them from the balancer. A
+/// "neg" response (namerd has nothing bound for the name) and any other/unparseable
+/// response carry no such signal and are treated as "no change" rather than "remove
+/// everything".
 fn parse_chunks(chunks: &[Chunk]) -> Option<Vec<::WeightedAddr>> {
     let r = to_buf(chunks).into_buf().reader();
     let result: json::Result<NamerdResponse> = json::from_reader(r);
     match result {
         Ok(ref nrsp) if nrsp.kind == "bound" => Some(to_weighted_addrs(&nrsp.addrs)),
-        Ok(ref nrsp) if nrsp.kind == "neg" => Some(vec![]),
-        Ok(_) => Some(vec![]),
+        Ok(ref nrsp) => {
+            info!("namerd response carries no bound addresses: {}", nrsp.kind);
+            None
+        }
         Err(e) => {
             error!("error parsing response: {}", e);
             None
@@ -146,7 +182,6 @@ fn parse_chunks(chunks: &[Chunk]) -> Option<Vec<::WeightedAddr>> {
 }
 
 fn to_weighted_addrs(namerd_addrs: &[NamerdAddr]) -> Vec<::WeightedAddr> {
-    // We never intentionally clear the EndpointMap.
     let mut weighted_addrs: Vec<::WeightedAddr> = Vec::new();
     for na in namerd_addrs {
         let addr = net::SocketAddr::new(na.ip.parse().unwrap(), na.port);